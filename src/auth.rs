@@ -0,0 +1,329 @@
+use crate::db::SharedStorage;
+use crate::error::{internal_server_error, unauthorized_error, AppError};
+use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use axum::extract::{FromRef, FromRequestParts, State};
+use axum::http::header::{COOKIE, SET_COOKIE};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Form;
+use log::{error, info, warn};
+use rand::RngCore;
+use serde::Deserialize;
+
+/// Name of the HttpOnly cookie holding the opaque session token.
+pub const SESSION_COOKIE: &str = "session";
+
+/// A registered account. The password is only ever kept as an Argon2 hash.
+#[derive(Debug, Clone)]
+pub struct User {
+    pub username: String,
+    pub password_hash: String,
+}
+
+/// Identity of the user behind an authenticated request, resolved from the
+/// session cookie. Handlers that require authentication take this as an
+/// argument; extraction rejects with `401` when no valid session is present.
+pub struct AuthUser {
+    pub username: String,
+}
+
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for AuthUser
+where
+    SharedStorage: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let storage = SharedStorage::from_ref(state);
+        let token = session_token(&parts.headers).ok_or_else(|| {
+            warn!("Request to a protected route without a session cookie");
+            unauthorized_error()
+        })?;
+        match storage.session_user(&token)? {
+            Some(username) => Ok(AuthUser { username }),
+            None => {
+                warn!("Session token did not match any active session");
+                Err(unauthorized_error())
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct LoginForm {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+pub struct RegisterForm {
+    username: String,
+    password: String,
+}
+
+// Create a new account with an Argon2-hashed password, then log the caller in
+// straight away, same as a successful `post_login`. Uniqueness of `username`
+// is enforced by the `users` table's primary key, not a check-then-insert
+// here, so two concurrent registrations for the same name can't both win.
+pub async fn post_register(
+    State(storage): State<SharedStorage>,
+    Form(form): Form<RegisterForm>,
+) -> Result<Response, AppError> {
+    let password_hash = hash_password(&form.password)?;
+    storage.create_user(&form.username, &password_hash)?;
+    info!("User {} registered", form.username);
+
+    let token = generate_token();
+    storage.create_session(&token, &form.username)?;
+
+    let mut response = Redirect::to("/home").into_response();
+    set_cookie(
+        &mut response,
+        &format!("{}={}; HttpOnly; Path=/; SameSite=Lax", SESSION_COOKIE, token),
+    )?;
+    Ok(response)
+}
+
+// Verify credentials, issue a server-side session and set it as an HttpOnly
+// cookie before redirecting back to the feed.
+pub async fn post_login(
+    State(storage): State<SharedStorage>,
+    Form(form): Form<LoginForm>,
+) -> Result<Response, AppError> {
+    let user = storage.get_user(&form.username)?.ok_or_else(|| {
+        warn!("Login attempt for unknown user {}", form.username);
+        unauthorized_error()
+    })?;
+    verify_password(&form.password, &user.password_hash)?;
+
+    let token = generate_token();
+    storage.create_session(&token, &user.username)?;
+    info!("User {} logged in", user.username);
+
+    let mut response = Redirect::to("/home").into_response();
+    set_cookie(
+        &mut response,
+        &format!("{}={}; HttpOnly; Path=/; SameSite=Lax", SESSION_COOKIE, token),
+    )?;
+    Ok(response)
+}
+
+// Drop the server-side session and clear the cookie.
+pub async fn post_logout(
+    State(storage): State<SharedStorage>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if let Some(token) = session_token(&headers) {
+        storage.delete_session(&token)?;
+    }
+    let mut response = Redirect::to("/home").into_response();
+    set_cookie(
+        &mut response,
+        &format!("{}=; HttpOnly; Path=/; SameSite=Lax; Max-Age=0", SESSION_COOKIE),
+    )?;
+    Ok(response)
+}
+
+// Hash a plaintext password for storage in the `users` table.
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| {
+            error!("Failed to hash password: {:?}", e);
+            internal_server_error()
+        })
+}
+
+fn verify_password(password: &str, hash: &str) -> Result<(), AppError> {
+    let parsed = PasswordHash::new(hash).map_err(|e| {
+        error!("Stored password hash is malformed: {:?}", e);
+        internal_server_error()
+    })?;
+    Argon2::default()
+        .verify_password(password.as_bytes(), &parsed)
+        .map_err(|_| {
+            warn!("Password verification failed");
+            unauthorized_error()
+        })
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+// Pull the session token out of the request's `Cookie` header, if present.
+fn session_token(headers: &HeaderMap) -> Option<String> {
+    let cookies = headers.get(COOKIE)?.to_str().ok()?;
+    cookies.split(';').find_map(|pair| {
+        let (name, value) = pair.trim().split_once('=')?;
+        (name == SESSION_COOKIE).then(|| value.to_string())
+    })
+}
+
+fn set_cookie(response: &mut Response, cookie: &str) -> Result<(), AppError> {
+    let value = HeaderValue::from_str(cookie).map_err(|e| {
+        error!("Failed to build Set-Cookie header: {:?}", e);
+        internal_server_error()
+    })?;
+    response.headers_mut().insert(SET_COOKIE, value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{SqliteStorage, Storage};
+    use axum::body::{to_bytes, Body};
+    use axum::extract::Request;
+    use axum::http::StatusCode;
+    use axum::routing::{get, post};
+    use axum::Router;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    fn test_storage() -> SharedStorage {
+        Arc::new(SqliteStorage::in_memory().unwrap())
+    }
+
+    async fn protected(user: AuthUser) -> String {
+        user.username
+    }
+
+    fn test_app(storage: SharedStorage) -> Router {
+        Router::new()
+            .route("/login", post(post_login))
+            .route("/logout", post(post_logout))
+            .route("/protected", get(protected))
+            .with_state(storage)
+    }
+
+    fn login_request(username: &str, password: &str) -> Request<Body> {
+        Request::builder()
+            .method("POST")
+            .uri("/login")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(Body::from(format!(
+                "username={}&password={}",
+                username, password
+            )))
+            .unwrap()
+    }
+
+    fn request_with_cookie(method: &str, uri: &str, cookie: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("cookie", cookie)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn session_cookie(response: &axum::response::Response) -> String {
+        response
+            .headers()
+            .get(SET_COOKIE)
+            .expect("login/logout should set a cookie")
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn protected_route_rejects_without_session() {
+        let response = test_app(test_storage())
+            .oneshot(
+                Request::builder()
+                    .uri("/protected")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn login_fails_for_unknown_user() {
+        let response = test_app(test_storage())
+            .oneshot(login_request("nobody", "whatever"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn login_fails_for_wrong_password() {
+        let storage = test_storage();
+        storage
+            .create_user("alice", &hash_password("hunter2").unwrap())
+            .unwrap();
+
+        let response = test_app(storage)
+            .oneshot(login_request("alice", "wrong"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn login_success_unlocks_protected_route() {
+        let storage = test_storage();
+        storage
+            .create_user("alice", &hash_password("hunter2").unwrap())
+            .unwrap();
+        let app = test_app(storage);
+
+        let login_response = app
+            .clone()
+            .oneshot(login_request("alice", "hunter2"))
+            .await
+            .unwrap();
+        let cookie = session_cookie(&login_response);
+
+        let response = app
+            .oneshot(request_with_cookie("GET", "/protected", &cookie))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "alice".as_bytes());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn logout_revokes_the_session() {
+        let storage = test_storage();
+        storage
+            .create_user("alice", &hash_password("hunter2").unwrap())
+            .unwrap();
+        let app = test_app(storage);
+
+        let login_response = app
+            .clone()
+            .oneshot(login_request("alice", "hunter2"))
+            .await
+            .unwrap();
+        let cookie = session_cookie(&login_response);
+
+        app.clone()
+            .oneshot(request_with_cookie("POST", "/logout", &cookie))
+            .await
+            .unwrap();
+
+        let response = app
+            .oneshot(request_with_cookie("GET", "/protected", &cookie))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -1,24 +1,55 @@
+use crate::auth::AuthUser;
 use crate::blogpost::Blogpost;
-use crate::db;
+use crate::db::{SharedStorage, Storage};
 use crate::error::{
     avatar_download_error, form_error, internal_server_error, invalid_avatar_url_error,
-    invalid_image_format_error, AppError,
+    invalid_image_format_error, payload_too_large_error, AppError,
 };
+use crate::image_ingest::{normalize, ImageIngestConfig, CANONICAL_CONTENT_TYPE};
+use crate::media::{FilesystemMediaStore, MediaId, MediaStore, OpenMedia, DEFAULT_MEDIA_ROOT};
 use askama::Template;
+use axum::body::Bytes;
 use axum::extract::multipart::Field;
-use axum::response::Html;
-use axum::{body::Bytes, extract::Multipart};
-use base64::{prelude::BASE64_STANDARD, Engine};
-use image::ImageFormat;
+use axum::extract::{Multipart, Path, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{Html, IntoResponse, Response};
 use log::{error, info, warn};
-use std::io::Cursor;
-use std::time::Duration;
+use rand::Rng;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
 use url::Url;
 
 #[derive(Template)]
 #[template(path = "base.html")]
 pub struct BlogTemplate {
     pub posts: Vec<Blogpost>,
+    pub logged_in: bool,
+}
+
+// Build the media store used to persist uploaded images and avatars. Created
+// on demand, mirroring how `db` opens a connection per call.
+fn media_store() -> FilesystemMediaStore {
+    FilesystemMediaStore::new(DEFAULT_MEDIA_ROOT)
+}
+
+// Image ingest settings (accepted input formats, thumbnail size), sourced from
+// the environment the same way `db::storage_from_env` resolves the database
+// backend. Created on demand alongside the media store until request state is
+// threaded through.
+fn ingest_config() -> ImageIngestConfig {
+    ImageIngestConfig::from_env()
+}
+
+// Decoding and resizing a near-max-dimension image is CPU-bound and far
+// slower than an SQLite write, so it would starve the Tokio runtime the same
+// way a synchronous database call would (see `db::blocking`). Route it
+// through `block_in_place` too.
+fn blocking<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    tokio::task::block_in_place(f)
 }
 
 pub async fn fallback(uri: axum::http::Uri) -> (axum::http::StatusCode, String) {
@@ -28,27 +59,221 @@ pub async fn fallback(uri: axum::http::Uri) -> (axum::http::StatusCode, String)
     )
 }
 
-pub async fn get_home() -> Result<Html<String>, AppError> {
+pub async fn get_home(
+    State(storage): State<SharedStorage>,
+    user: Option<AuthUser>,
+) -> Result<Html<String>, AppError> {
+    render_home(storage.as_ref(), user.is_some())
+}
+
+// Render the home feed from the given storage backend. Shared between the
+// `GET /home` handler and the post-submission redirect. `logged_in` drives the
+// login/logout control shown in the template.
+fn render_home(storage: &dyn Storage, logged_in: bool) -> Result<Html<String>, AppError> {
     info!("Fetching all blog posts for the home page");
-    match db::get_all_blogposts() {
-        Ok(posts) => {
-            info!("Rendering the home page with {} blog posts", posts.len());
+    let posts = storage.get_all_blogposts()?;
+    info!("Rendering the home page with {} blog posts", posts.len());
+
+    BlogTemplate { posts, logged_in }
+        .render()
+        .map(Html)
+        .map_err(|e| {
+            error!("Failed to render the template: {:?}", e);
+            internal_server_error()
+        })
+}
+
+// Media blobs are content-addressed and therefore immutable, so we let clients
+// cache them for a year.
+const MEDIA_MAX_AGE_SECS: u64 = 31_536_000;
+
+// Serve a stored blob by id with a strong ETag, Last-Modified, immutable
+// caching, and support for conditional (304) and ranged (206) requests. The
+// body is streamed straight off disk rather than buffered, so serving a large
+// file doesn't hold the whole thing in memory.
+pub async fn get_media(
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let media_id = MediaId::parse(&id)?;
+    let store = media_store();
+    let media = store.open(&media_id).await?;
+    let modified = store.modified(&media_id).await?;
+    let etag = format!("\"{}\"", media_id);
+
+    if if_none_match_hits(&headers, &etag) || if_modified_since_hits(&headers, modified) {
+        info!("Media {} unchanged, returning 304", media_id);
+        let mut response = Response::new(axum::body::Body::empty());
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        apply_caching_headers(response.headers_mut(), &media.content_type, &etag, modified);
+        return Ok(response);
+    }
+
+    if let Some(range) = headers.get(header::RANGE) {
+        return serve_range(range, media, &etag, modified).await;
+    }
+
+    let OpenMedia {
+        file,
+        size,
+        content_type,
+    } = media;
+    let stream = ReaderStream::new(file);
+    let mut response = Response::new(axum::body::Body::from_stream(stream));
+    apply_caching_headers(response.headers_mut(), &content_type, &etag, modified);
+    let headers = response.headers_mut();
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Ok(value) = HeaderValue::from_str(&size.to_string()) {
+        headers.insert(header::CONTENT_LENGTH, value);
+    }
+    Ok(response)
+}
+
+// Fill in the headers shared by full, partial and 304 responses.
+fn apply_caching_headers(
+    headers: &mut HeaderMap,
+    content_type: &str,
+    etag: &str,
+    modified: Option<SystemTime>,
+) {
+    if let Ok(value) = HeaderValue::from_str(content_type) {
+        headers.insert(header::CONTENT_TYPE, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        headers.insert(header::ETAG, value);
+    }
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_str(&format!("public, max-age={}, immutable", MEDIA_MAX_AGE_SECS))
+            .expect("cache-control is valid"),
+    );
+    if let Some(value) = modified.and_then(format_http_date).and_then(|d| HeaderValue::from_str(&d).ok()) {
+        headers.insert(header::LAST_MODIFIED, value);
+    }
+}
 
-            BlogTemplate { posts }.render().map(Html).map_err(|e| {
-                error!("Failed to render the template: {:?}", e);
+// Build a 206/416 response for a single-range request by seeking into the
+// open file and streaming only the requested span, falling back to a 416 when
+// the range header is malformed or unsatisfiable.
+async fn serve_range(
+    range: &HeaderValue,
+    media: OpenMedia,
+    etag: &str,
+    modified: Option<SystemTime>,
+) -> Result<Response, AppError> {
+    let OpenMedia {
+        mut file,
+        size: total,
+        content_type,
+    } = media;
+
+    match range.to_str().ok().and_then(|r| parse_single_range(r, total)) {
+        Some((start, end)) => {
+            let len = end - start + 1;
+            file.seek(std::io::SeekFrom::Start(start)).await.map_err(|e| {
+                error!("Failed to seek into media file for a range request: {:?}", e);
                 internal_server_error()
-            })
+            })?;
+            let stream = ReaderStream::new(file.take(len));
+            let mut response =
+                (StatusCode::PARTIAL_CONTENT, axum::body::Body::from_stream(stream))
+                    .into_response();
+            apply_caching_headers(response.headers_mut(), &content_type, etag, modified);
+            let headers = response.headers_mut();
+            headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+            if let Ok(value) =
+                HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, total))
+            {
+                headers.insert(header::CONTENT_RANGE, value);
+            }
+            if let Ok(value) = HeaderValue::from_str(&len.to_string()) {
+                headers.insert(header::CONTENT_LENGTH, value);
+            }
+            Ok(response)
+        }
+        None => {
+            warn!("Unsatisfiable Range header for media request");
+            let mut response = StatusCode::RANGE_NOT_SATISFIABLE.into_response();
+            if let Ok(value) = HeaderValue::from_str(&format!("bytes */{}", total)) {
+                response.headers_mut().insert(header::CONTENT_RANGE, value);
+            }
+            Ok(response)
+        }
+    }
+}
+
+// Parse a single `bytes=start-end` range against a known length, returning an
+// inclusive, clamped `(start, end)` or `None` when unsatisfiable.
+fn parse_single_range(raw: &str, total: u64) -> Option<(u64, u64)> {
+    let spec = raw.strip_prefix("bytes=")?;
+    if spec.contains(',') || total == 0 {
+        return None;
+    }
+    let (start_str, end_str) = spec.split_once('-')?;
+
+    let (start, end) = match (start_str.trim(), end_str.trim()) {
+        // Suffix range: last N bytes.
+        ("", suffix) => {
+            let n: u64 = suffix.parse().ok()?;
+            if n == 0 {
+                return None;
+            }
+            (total.saturating_sub(n), total - 1)
         }
-        Err(_) => Err(internal_server_error()),
+        // Open-ended range: from start to the end of the blob.
+        (start, "") => (start.parse().ok()?, total - 1),
+        // Closed range.
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(total - 1)),
+    };
+
+    if start > end || start >= total {
+        None
+    } else {
+        Some((start, end))
     }
 }
 
+fn if_none_match_hits(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value == "*" || value.split(',').any(|tag| tag.trim() == etag))
+        .unwrap_or(false)
+}
+
+fn if_modified_since_hits(headers: &HeaderMap, modified: Option<SystemTime>) -> bool {
+    let (Some(modified), Some(since)) = (
+        modified,
+        headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_http_date),
+    ) else {
+        return false;
+    };
+    modified <= since
+}
+
+// Format a timestamp as an RFC 7231 IMF-fixdate (`Sun, 06 Nov 1994 08:49:37 GMT`).
+fn format_http_date(time: SystemTime) -> Option<String> {
+    let datetime: chrono::DateTime<chrono::Utc> = time.into();
+    Some(datetime.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
+
+fn parse_http_date(raw: &str) -> Option<SystemTime> {
+    chrono::NaiveDateTime::parse_from_str(raw.trim(), "%a, %d %b %Y %H:%M:%S GMT")
+        .ok()
+        .map(|naive| SystemTime::from(naive.and_utc()))
+}
+
 pub async fn handle_form_submit(
-    multipart: axum::extract::Multipart,
+    State(storage): State<SharedStorage>,
+    user: AuthUser,
+    multipart: Multipart,
 ) -> Result<Html<String>, AppError> {
-    info!("Handling form submission");
+    info!("Handling form submission for {}", user.username);
 
-    let multipart_data = match parse_multipart(multipart).await {
+    let mut multipart_data = match parse_multipart(multipart, &upload_limits()).await {
         Ok(data) => {
             info!("Parsed multipart form data: {:?}", data);
             data
@@ -59,6 +284,9 @@ pub async fn handle_form_submit(
         }
     };
 
+    // Never trust the client-supplied author: use the authenticated identity.
+    multipart_data.author_username = user.username;
+
     let new_post = match create_blogpost(multipart_data).await {
         Ok(post) => post,
         Err(e) => {
@@ -67,13 +295,13 @@ pub async fn handle_form_submit(
         }
     };
 
-    if db::insert_blogpost(new_post).is_err() {
+    if storage.insert_blogpost(new_post).is_err() {
         error!("Failed to insert blogpost into database");
         return Err(internal_server_error());
     }
 
     info!("New blogpost successfully inserted into database");
-    get_home().await
+    render_home(storage.as_ref(), true)
 }
 
 async fn create_blogpost(multipart_data: MultipartData) -> Result<Blogpost, AppError> {
@@ -82,7 +310,8 @@ async fn create_blogpost(multipart_data: MultipartData) -> Result<Blogpost, AppE
     let mut new_post = Blogpost::new(
         multipart_data.text,
         multipart_data.author_username,
-        multipart_data.image_base64,
+        multipart_data.image_id,
+        multipart_data.image_thumb_id,
         None,
     );
 
@@ -91,9 +320,9 @@ async fn create_blogpost(multipart_data: MultipartData) -> Result<Blogpost, AppE
         info!("Avatar URL parsed successfully: {}", parsed_url);
 
         match download_avatar(parsed_url).await {
-            Ok(avatar_base64) => {
-                info!("Avatar downloaded and encoded successfully");
-                new_post.avatar_base64 = avatar_base64;
+            Ok(avatar_id) => {
+                info!("Avatar downloaded and stored successfully");
+                new_post.avatar_id = avatar_id;
             }
             Err(e) => {
                 error!("Failed to download avatar: {:?}", e);
@@ -106,8 +335,41 @@ async fn create_blogpost(multipart_data: MultipartData) -> Result<Blogpost, AppE
     Ok(new_post)
 }
 
-// Download a png avatar from the given URL and return it as a base64 encoded string
-async fn download_avatar(url: Url) -> Result<Option<String>, AppError> {
+// Controls how hard `download_avatar` retries transient upstream failures.
+// Exposed so tests can drive the loop with tiny delays.
+#[derive(Clone, Debug)]
+struct AvatarRetryConfig {
+    /// Total number of attempts, including the first.
+    max_attempts: u32,
+    /// Delay before the first retry; doubles on each subsequent attempt.
+    base_delay: Duration,
+    /// Upper bound on the exponential delay before jitter is added.
+    max_delay: Duration,
+    /// Maximum random additive term mixed into each delay.
+    jitter: Duration,
+}
+
+impl Default for AvatarRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+            jitter: Duration::from_millis(100),
+        }
+    }
+}
+
+// Download a png avatar from the given URL, persist it through the media store
+// and return the id it was stored under
+async fn download_avatar(url: Url) -> Result<Option<MediaId>, AppError> {
+    download_avatar_with_config(url, &AvatarRetryConfig::default()).await
+}
+
+async fn download_avatar_with_config(
+    url: Url,
+    config: &AvatarRetryConfig,
+) -> Result<Option<MediaId>, AppError> {
     info!("Downloading avatar from URL: {}", url);
     let client = reqwest::ClientBuilder::new()
         .timeout(Duration::from_secs(5))
@@ -117,24 +379,88 @@ async fn download_avatar(url: Url) -> Result<Option<String>, AppError> {
             internal_server_error()
         })?;
 
-    let request = client
-        .get(url.clone())
-        .header("Accept", "image/png")
-        .build()
-        .map_err(|e| {
-            error!("Failed to build request for URL {}: {:?}", url, e);
-            internal_server_error()
-        })?;
+    let mut attempt = 0;
+    loop {
+        let request = client
+            .get(url.clone())
+            .header("Accept", "image/png")
+            .build()
+            .map_err(|e| {
+                error!("Failed to build request for URL {}: {:?}", url, e);
+                internal_server_error()
+            })?;
 
-    let response = client.execute(request).await.map_err(|e| {
-        error!("Request execution failed for URL {}: {:?}", url, e);
-        avatar_download_error()
-    })?;
+        let last_attempt = attempt + 1 >= config.max_attempts;
+        match client.execute(request).await {
+            Ok(response) if response.status().is_success() => {
+                return handle_avatar_response(response).await;
+            }
+            Ok(response) if !last_attempt && is_retryable_status(response.status()) => {
+                let delay = retry_after(&response, config).unwrap_or_else(|| backoff_delay(config, attempt));
+                warn!(
+                    "Retryable avatar response {} from {}, retrying in {:?}",
+                    response.status(),
+                    url,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+            }
+            Ok(response) => {
+                error!(
+                    "Giving up on avatar download from {}: status {}",
+                    url,
+                    response.status()
+                );
+                return Err(avatar_download_error());
+            }
+            Err(e) if !last_attempt => {
+                let delay = backoff_delay(config, attempt);
+                warn!("Avatar request to {} failed ({:?}), retrying in {:?}", url, e, delay);
+                tokio::time::sleep(delay).await;
+            }
+            Err(e) => {
+                error!("Request execution failed for URL {}: {:?}", url, e);
+                return Err(avatar_download_error());
+            }
+        }
+        attempt += 1;
+    }
+}
+
+// Statuses worth retrying: upstream gateway hiccups and explicit rate limiting.
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    matches!(status.as_u16(), 429 | 502 | 503 | 504)
+}
 
-    handle_avatar_response(response).await
+// Honor a `Retry-After` header (delta-seconds or an HTTP date) when present,
+// clamped to `config.max_delay` so a misbehaving upstream can't stall the
+// handling task indefinitely.
+fn retry_after(response: &reqwest::Response, config: &AvatarRetryConfig) -> Option<Duration> {
+    let raw = response.headers().get("Retry-After")?.to_str().ok()?;
+    let delay = if let Ok(secs) = raw.trim().parse::<u64>() {
+        Duration::from_secs(secs)
+    } else {
+        let when = parse_http_date(raw)?;
+        when.duration_since(SystemTime::now()).ok()?
+    };
+    Some(delay.min(config.max_delay))
 }
 
-async fn handle_avatar_response(response: reqwest::Response) -> Result<Option<String>, AppError> {
+// Exponential backoff with jitter: base * 2^attempt, capped, plus a small
+// random additive term to avoid synchronized retries.
+fn backoff_delay(config: &AvatarRetryConfig, attempt: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempt);
+    let capped = config.base_delay.saturating_mul(factor).min(config.max_delay);
+    let jitter_ms = config.jitter.as_millis() as u64;
+    let jitter = if jitter_ms == 0 {
+        Duration::ZERO
+    } else {
+        Duration::from_millis(rand::thread_rng().gen_range(0..=jitter_ms))
+    };
+    capped + jitter
+}
+
+async fn handle_avatar_response(response: reqwest::Response) -> Result<Option<MediaId>, AppError> {
     if !response.status().is_success() {
         error!(
             "Received non-success response for avatar download: {}",
@@ -143,47 +469,49 @@ async fn handle_avatar_response(response: reqwest::Response) -> Result<Option<St
         return Err(avatar_download_error());
     }
 
-    validate_png_header(&response)?;
-    let bytes = response.bytes().await.map_err(|e| {
-        error!("Failed to read response bytes for avatar: {:?}", e);
-        avatar_download_error()
-    })?;
-    validate_bytes_as_png(&bytes)?;
-    let rv = BASE64_STANDARD.encode(bytes);
-    Ok(Some(rv))
+    validate_image_header(&response)?;
+    let bytes = read_response_bounded(response, upload_limits().max_field_bytes).await?;
+    // Re-encode the remote avatar through the same normalization as uploads so
+    // it is never served back to clients verbatim.
+    let normalized = blocking(|| normalize(&bytes, &ingest_config()))?;
+    let id = media_store()
+        .store(normalized.full_png, CANONICAL_CONTENT_TYPE)
+        .await?;
+    Ok(Some(id))
 }
 
-// Verify that the bytes downloaded from a given URL are a valid PNG image
-fn validate_bytes_as_png(image_bytes: &Bytes) -> Result<(), AppError> {
-    info!("Validating PNG image format");
-    match image::ImageReader::new(Cursor::new(image_bytes))
-        .with_guessed_format()
-        .map_err(|e| {
-            error!("Failed to guess image format: {:?}", e);
-            internal_server_error()
-        })?
-        .format()
-    {
-        Some(ImageFormat::Png) => Ok(()),
-        Some(_) => {
-            warn!("Invalid image format detected (not PNG)");
-            Err(invalid_image_format_error())
-        }
-        None => {
-            warn!("No image format detected");
-            Err(invalid_image_format_error())
+// Stream a response body chunk by chunk, rejecting with a 413-style error once
+// it would exceed `limit`, so a malicious upstream can't balloon our memory.
+async fn read_response_bounded(
+    mut response: reqwest::Response,
+    limit: u64,
+) -> Result<Bytes, AppError> {
+    let mut buffer: Vec<u8> = Vec::new();
+
+    while let Some(chunk) = response.chunk().await.map_err(|e| {
+        error!("Failed to read avatar response chunk: {:?}", e);
+        avatar_download_error()
+    })? {
+        if buffer.len() as u64 + chunk.len() as u64 > limit {
+            warn!("Avatar response body exceeded the size limit");
+            return Err(payload_too_large_error());
         }
+        buffer.extend_from_slice(&chunk);
     }
+
+    Ok(Bytes::from(buffer))
 }
 
-// Verify that the Content-Type header of the a response is image/png
-fn validate_png_header(response: &reqwest::Response) -> Result<(), AppError> {
+// Verify that the Content-Type header advertises one of the accepted input
+// formats before we commit to downloading and decoding the body.
+fn validate_image_header(response: &reqwest::Response) -> Result<(), AppError> {
+    let config = ingest_config();
     let content_type = response
         .headers()
         .get("Content-Type")
         .ok_or_else(|| {
             warn!("Content-Type header missing in response");
-            invalid_image_format_error()
+            invalid_image_format_error(&config.accepted_formats)
         })?
         .to_str()
         .map_err(|e| {
@@ -191,9 +519,9 @@ fn validate_png_header(response: &reqwest::Response) -> Result<(), AppError> {
             internal_server_error()
         })?;
 
-    if content_type != "image/png" {
-        warn!("Invalid Content-Type (not image/png): {}", content_type);
-        return Err(invalid_image_format_error());
+    if !config.accepts_content_type(content_type) {
+        warn!("Unsupported avatar Content-Type: {}", content_type);
+        return Err(invalid_image_format_error(&config.accepted_formats));
     }
     Ok(())
 }
@@ -202,7 +530,8 @@ struct MultipartData {
     author_username: String,
     text: String,
     avatar_url: Option<String>,
-    image_base64: Option<String>,
+    image_id: Option<MediaId>,
+    image_thumb_id: Option<MediaId>,
 }
 
 impl std::fmt::Debug for MultipartData {
@@ -211,41 +540,86 @@ impl std::fmt::Debug for MultipartData {
             .field("author_username", &self.author_username)
             .field("text", &self.text)
             .field("avatar_url", &self.avatar_url)
-            .field(
-                "image_base64",
-                &self
-                    .image_base64
-                    .as_ref()
-                    .map(|s| format!("{}...", &s[..20])),
-            )
+            .field("image_id", &self.image_id)
+            .field("image_thumb_id", &self.image_thumb_id)
             .finish()
     }
 }
 
-async fn parse_multipart(mut multipart: Multipart) -> Result<MultipartData, AppError> {
+// Caps on how much a single multipart submission may stream into memory.
+// Enforced incrementally so an oversized field is rejected before it is fully
+// buffered.
+#[derive(Clone, Debug)]
+struct UploadLimits {
+    /// Maximum bytes accepted from any single field.
+    max_field_bytes: u64,
+    /// Maximum bytes accepted across all fields of one request.
+    max_request_bytes: u64,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        Self {
+            max_field_bytes: 10 * 1024 * 1024,
+            max_request_bytes: 12 * 1024 * 1024,
+        }
+    }
+}
+
+fn upload_limits() -> UploadLimits {
+    UploadLimits::default()
+}
+
+/// The request-wide upload cap, exposed so `main::app` can raise axum's
+/// built-in `DefaultBodyLimit` to match — otherwise that layer rejects
+/// anything over its own 2MB default before our streaming checks ever run.
+pub(crate) fn max_request_body_bytes() -> usize {
+    upload_limits().max_request_bytes as usize
+}
+
+async fn parse_multipart(
+    mut multipart: Multipart,
+    limits: &UploadLimits,
+) -> Result<MultipartData, AppError> {
     info!("Parsing multipart form data");
 
     let mut data = MultipartData {
         author_username: String::new(),
         text: String::new(),
         avatar_url: None,
-        image_base64: None,
+        image_id: None,
+        image_thumb_id: None,
     };
 
+    let mut request_remaining = limits.max_request_bytes;
+
     while let Some(field) = multipart.next_field().await.map_err(|e| {
         error!("Failed to fetch next multipart field: {:?}", e);
         form_error()
     })? {
-        let name = field.name().ok_or_else(|| {
-            warn!("Multipart field without a name encountered");
-            form_error()
-        })?;
-
-        match name {
-            "text" => data.text = parse_text_field(field).await?,
-            "author_username" => data.author_username = parse_author_username_field(field).await?,
-            "image" => data.image_base64 = parse_image_field(field).await?,
-            "avatar_url" => data.avatar_url = parse_avatar_url_field(field).await?,
+        let name = field
+            .name()
+            .ok_or_else(|| {
+                warn!("Multipart field without a name encountered");
+                form_error()
+            })?
+            .to_string();
+
+        let bytes =
+            read_field_bounded(field, limits.max_field_bytes, &mut request_remaining).await?;
+
+        match name.as_str() {
+            "text" => data.text = field_to_string(bytes)?,
+            "author_username" => data.author_username = field_to_string(bytes)?,
+            "image" => {
+                if let Some((image_id, thumb_id)) = store_image(bytes).await? {
+                    data.image_id = Some(image_id);
+                    data.image_thumb_id = Some(thumb_id);
+                }
+            }
+            "avatar_url" => {
+                data.avatar_url = Some(field_to_string(bytes)?).filter(|x| !x.is_empty());
+            }
             _ => warn!("Unexpected field in multipart data: {}", name),
         }
     }
@@ -254,40 +628,59 @@ async fn parse_multipart(mut multipart: Multipart) -> Result<MultipartData, AppE
     Ok(data)
 }
 
-async fn parse_text_field(field: Field<'_>) -> Result<String, AppError> {
-    field.text().await.map_err(|e| {
-        error!("Failed to read 'text' field: {:?}", e);
+// Drain a field chunk by chunk, rejecting with a 413-style error as soon as the
+// running total would exceed either the per-field or per-request budget, so no
+// more than the limit is ever buffered.
+async fn read_field_bounded(
+    mut field: Field<'_>,
+    field_limit: u64,
+    request_remaining: &mut u64,
+) -> Result<Bytes, AppError> {
+    let mut buffer: Vec<u8> = Vec::new();
+
+    while let Some(chunk) = field.chunk().await.map_err(|e| {
+        error!("Failed to read multipart chunk: {:?}", e);
         form_error()
-    })
+    })? {
+        let len = chunk.len() as u64;
+        if buffer.len() as u64 + len > field_limit {
+            warn!("Field exceeded the per-field size limit");
+            return Err(payload_too_large_error());
+        }
+        if len > *request_remaining {
+            warn!("Request exceeded the total upload size limit");
+            return Err(payload_too_large_error());
+        }
+        *request_remaining -= len;
+        buffer.extend_from_slice(&chunk);
+    }
+
+    Ok(Bytes::from(buffer))
 }
 
-async fn parse_author_username_field(field: Field<'_>) -> Result<String, AppError> {
-    field.text().await.map_err(|e| {
-        error!("Failed to read 'author_username' field: {:?}", e);
+fn field_to_string(bytes: Bytes) -> Result<String, AppError> {
+    String::from_utf8(bytes.to_vec()).map_err(|e| {
+        error!("Field was not valid UTF-8: {:?}", e);
         form_error()
     })
 }
 
-async fn parse_image_field(field: Field<'_>) -> Result<Option<String>, AppError> {
-    let bytes = field.bytes().await.map_err(|e| {
-        error!("Failed to read 'image' field: {:?}", e);
-        form_error()
-    })?;
-
-    if !bytes.is_empty() {
-        validate_bytes_as_png(&bytes)?;
-        Ok(Some(BASE64_STANDARD.encode(bytes)))
-    } else {
-        Ok(None)
+// Normalize an uploaded image and persist both the full PNG and its thumbnail,
+// returning the `(full, thumbnail)` media ids.
+async fn store_image(bytes: Bytes) -> Result<Option<(MediaId, MediaId)>, AppError> {
+    if bytes.is_empty() {
+        return Ok(None);
     }
-}
 
-async fn parse_avatar_url_field(field: Field<'_>) -> Result<Option<String>, AppError> {
-    let text = field.text().await.map_err(|e| {
-        error!("Failed to read 'avatar_url' field: {:?}", e);
-        form_error()
-    })?;
-    Ok(Some(text).filter(|x| !x.is_empty()))
+    let normalized = blocking(|| normalize(&bytes, &ingest_config()))?;
+    let store = media_store();
+    let image_id = store
+        .store(normalized.full_png, CANONICAL_CONTENT_TYPE)
+        .await?;
+    let thumb_id = store
+        .store(normalized.thumbnail_png, CANONICAL_CONTENT_TYPE)
+        .await?;
+    Ok(Some((image_id, thumb_id)))
 }
 
 #[cfg(test)]
@@ -360,8 +753,15 @@ mod tests {
         let result_wrong = download_avatar(server_url_wrong).await;
         let result_none = download_avatar(server_url_none).await;
 
-        assert_eq!(result_wrong, Err(invalid_image_format_error()));
-        assert_eq!(result_none, Err(invalid_image_format_error()));
+        let accepted_formats = ingest_config().accepted_formats;
+        assert_eq!(
+            result_wrong,
+            Err(invalid_image_format_error(&accepted_formats))
+        );
+        assert_eq!(
+            result_none,
+            Err(invalid_image_format_error(&accepted_formats))
+        );
     }
 
     #[tokio::test]
@@ -391,7 +791,56 @@ mod tests {
 
         let server_url = url::Url::parse(&server.url()).unwrap();
         let result = download_avatar(server_url).await;
-        assert_eq!(result, Err(invalid_image_format_error()));
+        assert_eq!(
+            result,
+            Err(invalid_image_format_error(&ingest_config().accepted_formats))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_avatar_retries_then_gives_up() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/")
+            .with_status(503)
+            .expect(3)
+            .create_async()
+            .await;
+
+        let config = AvatarRetryConfig {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+            jitter: Duration::ZERO,
+        };
+        let server_url = url::Url::parse(&server.url()).unwrap();
+        let result = download_avatar_with_config(server_url, &config).await;
+
+        assert_eq!(result, Err(avatar_download_error()));
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_download_avatar_does_not_retry_non_retryable_status() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/")
+            .with_status(404)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let config = AvatarRetryConfig {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+            jitter: Duration::ZERO,
+        };
+        let server_url = url::Url::parse(&server.url()).unwrap();
+        let result = download_avatar_with_config(server_url, &config).await;
+
+        assert_eq!(result, Err(avatar_download_error()));
+        mock.assert_async().await;
     }
 
     #[tokio::test]
@@ -407,6 +856,96 @@ mod tests {
 
         let server_url = url::Url::parse(&server.url()).unwrap();
         let result = download_avatar(server_url).await;
-        assert_eq!(result, Err(invalid_image_format_error()));
+        assert_eq!(
+            result,
+            Err(invalid_image_format_error(&ingest_config().accepted_formats))
+        );
+    }
+
+    #[test]
+    fn parse_single_range_closed() {
+        assert_eq!(parse_single_range("bytes=0-9", 100), Some((0, 9)));
+    }
+
+    #[test]
+    fn parse_single_range_open_ended() {
+        assert_eq!(parse_single_range("bytes=90-", 100), Some((90, 99)));
+    }
+
+    #[test]
+    fn parse_single_range_suffix() {
+        assert_eq!(parse_single_range("bytes=-10", 100), Some((90, 99)));
+    }
+
+    #[test]
+    fn parse_single_range_clamps_end_to_total() {
+        assert_eq!(parse_single_range("bytes=0-999", 100), Some((0, 99)));
+    }
+
+    #[test]
+    fn parse_single_range_rejects_start_past_total() {
+        assert_eq!(parse_single_range("bytes=100-200", 100), None);
+    }
+
+    #[test]
+    fn parse_single_range_rejects_inverted_range() {
+        assert_eq!(parse_single_range("bytes=50-10", 100), None);
+    }
+
+    #[test]
+    fn parse_single_range_rejects_zero_length_suffix() {
+        assert_eq!(parse_single_range("bytes=-0", 100), None);
+    }
+
+    #[test]
+    fn parse_single_range_rejects_multiple_ranges() {
+        assert_eq!(parse_single_range("bytes=0-9,20-29", 100), None);
+    }
+
+    #[test]
+    fn parse_single_range_rejects_missing_prefix() {
+        assert_eq!(parse_single_range("0-9", 100), None);
+    }
+
+    #[test]
+    fn parse_single_range_rejects_empty_store() {
+        assert_eq!(parse_single_range("bytes=0-9", 0), None);
+    }
+
+    #[test]
+    fn if_none_match_hits_exact_etag() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"abc\""));
+        assert!(if_none_match_hits(&headers, "\"abc\""));
+        assert!(!if_none_match_hits(&headers, "\"def\""));
+    }
+
+    #[test]
+    fn if_none_match_hits_wildcard() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("*"));
+        assert!(if_none_match_hits(&headers, "\"anything\""));
+    }
+
+    #[test]
+    fn if_modified_since_hits_when_not_newer() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Thu, 01 Jan 1970 00:20:00 GMT"),
+        );
+        assert!(if_modified_since_hits(&headers, Some(modified)));
+    }
+
+    #[test]
+    fn if_modified_since_misses_when_newer() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(10_000);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Thu, 01 Jan 1970 00:20:00 GMT"),
+        );
+        assert!(!if_modified_since_hits(&headers, Some(modified)));
     }
 }
@@ -1,28 +1,31 @@
+use crate::media::MediaId;
 use chrono::NaiveDateTime;
-use serde::Deserialize;
 
-#[derive(Deserialize, Clone)]
+#[derive(Clone)]
 pub struct Blogpost {
     pub text: String,
     pub author_username: String,
     pub published: NaiveDateTime,
-    pub image_base64: Option<String>,
-    pub avatar_base64: Option<String>,
+    pub image_id: Option<MediaId>,
+    pub image_thumb_id: Option<MediaId>,
+    pub avatar_id: Option<MediaId>,
 }
 
 impl Blogpost {
     pub fn new(
         text: String,
         author_username: String,
-        image_base64: Option<String>,
-        avatar_base64: Option<String>,
+        image_id: Option<MediaId>,
+        image_thumb_id: Option<MediaId>,
+        avatar_id: Option<MediaId>,
     ) -> Self {
         Self {
             text,
             author_username,
             published: chrono::Local::now().naive_local(),
-            image_base64,
-            avatar_base64,
+            image_id,
+            image_thumb_id,
+            avatar_id,
         }
     }
 
@@ -30,9 +33,22 @@ impl Blogpost {
         Self {
             text: row.get(0).unwrap(),
             published: row.get(1).unwrap(),
-            image_base64: row.get(2).unwrap(),
-            author_username: row.get(3).unwrap(),
-            avatar_base64: row.get(4).unwrap(),
+            image_id: row.get(2).unwrap(),
+            image_thumb_id: row.get(3).unwrap(),
+            author_username: row.get(4).unwrap(),
+            avatar_id: row.get(5).unwrap(),
+        }
+    }
+
+    pub fn from_postgres_row(row: &postgres::Row) -> Self {
+        let media = |idx: usize| row.get::<_, Option<String>>(idx).map(MediaId::from_trusted);
+        Self {
+            text: row.get(0),
+            published: row.get(1),
+            image_id: media(2),
+            image_thumb_id: media(3),
+            author_username: row.get(4),
+            avatar_id: media(5),
         }
     }
 }
@@ -42,20 +58,9 @@ impl std::fmt::Debug for Blogpost {
         f.debug_struct("Blogpost")
             .field("author_username", &self.author_username)
             .field("text", &self.text)
-            .field(
-                "image_base64",
-                &self
-                    .image_base64
-                    .as_ref()
-                    .map(|s| format!("{}...", &s[..20])),
-            )
-            .field(
-                "avatar_base64",
-                &self
-                    .avatar_base64
-                    .as_ref()
-                    .map(|s| format!("{}...", &s[..20])),
-            )
+            .field("image_id", &self.image_id)
+            .field("image_thumb_id", &self.image_thumb_id)
+            .field("avatar_id", &self.avatar_id)
             .finish()
     }
 }
@@ -1,61 +1,442 @@
+use crate::auth::User;
 use crate::blogpost::Blogpost;
-use rusqlite::{params, Connection, Result};
-
-pub fn create_db_connection() -> Result<Connection> {
-    let conn = Connection::open("blog.db")?;
-    Ok(conn)
-}
-
-pub fn create_db_schema() -> Result<()> {
-    let conn = create_db_connection()?;
-    conn.execute(
-        "
-        CREATE TABLE IF NOT EXISTS blogposts (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            text TEXT NOT NULL,
-            publication_date DATE NOT NULL,
-            image TEXT,
-            username TEXT NOT NULL,
-            avatar TEXT
-        );
-        ",
-        [],
-    )?;
-
-    Ok(())
-}
-
-pub fn insert_blogpost(blogpost: Blogpost) -> Result<()> {
-    create_db_connection()?.execute(
-        "
-        INSERT INTO blogposts (text, publication_date, image, username, avatar)
-        VALUES (?1, ?2, ?3, ?4, ?5);
-        ",
-        params![
-            blogpost.text,
-            blogpost.published,
-            blogpost.image_base64,
-            blogpost.author_username,
-            blogpost.avatar_base64,
-        ],
-    )?;
-    Ok(())
-}
-
-pub fn get_all_blogposts() -> Result<Vec<Blogpost>> {
-    let conn = create_db_connection()?;
-    let mut stmt = conn.prepare(
-        "
-        SELECT text, publication_date, image, username, avatar
-        FROM blogposts;
-        ",
-    )?;
-    let blogposts = stmt
-        .query_map([], |row| Ok(Blogpost::from_sqlite_row(row)))?
-        .collect::<Result<Vec<Blogpost>>>()?
-        .into_iter()
-        .rev()
-        .collect();
-
-    Ok(blogposts)
+use crate::error::{internal_server_error, username_taken_error, AppError};
+use crate::media::MediaId;
+use log::{error, info, warn};
+use r2d2_postgres::PostgresConnectionManager;
+use r2d2_sqlite::SqliteConnectionManager;
+use std::sync::Arc;
+
+/// Shared, cloneable handle to whichever backend the app was started with.
+pub type SharedStorage = Arc<dyn Storage>;
+
+const SCHEMA_SQLITE: &str = "
+    CREATE TABLE IF NOT EXISTS blogposts (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        text TEXT NOT NULL,
+        publication_date DATE NOT NULL,
+        image TEXT,
+        image_thumbnail TEXT,
+        username TEXT NOT NULL,
+        avatar TEXT
+    );
+    CREATE TABLE IF NOT EXISTS users (
+        username TEXT PRIMARY KEY,
+        password_hash TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS sessions (
+        token TEXT PRIMARY KEY,
+        username TEXT NOT NULL,
+        created DATE NOT NULL
+    );
+";
+
+const SCHEMA_POSTGRES: &str = "
+    CREATE TABLE IF NOT EXISTS blogposts (
+        id SERIAL PRIMARY KEY,
+        text TEXT NOT NULL,
+        publication_date TIMESTAMP NOT NULL,
+        image TEXT,
+        image_thumbnail TEXT,
+        username TEXT NOT NULL,
+        avatar TEXT
+    );
+    CREATE TABLE IF NOT EXISTS users (
+        username TEXT PRIMARY KEY,
+        password_hash TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS sessions (
+        token TEXT PRIMARY KEY,
+        username TEXT NOT NULL,
+        created TIMESTAMP NOT NULL
+    );
+";
+
+const SELECT_ALL: &str = "
+    SELECT text, publication_date, image, image_thumbnail, username, avatar
+    FROM blogposts
+";
+
+/// Persistence backend for blogposts. Implemented by both the SQLite and
+/// Postgres storages so handlers can operate against a trait object.
+pub trait Storage: Send + Sync {
+    fn create_schema(&self) -> Result<(), AppError>;
+    fn insert_blogpost(&self, blogpost: Blogpost) -> Result<(), AppError>;
+    fn get_all_blogposts(&self) -> Result<Vec<Blogpost>, AppError>;
+    fn get_by_id(&self, id: i64) -> Result<Option<Blogpost>, AppError>;
+
+    fn create_user(&self, username: &str, password_hash: &str) -> Result<(), AppError>;
+    fn get_user(&self, username: &str) -> Result<Option<User>, AppError>;
+    fn create_session(&self, token: &str, username: &str) -> Result<(), AppError>;
+    fn session_user(&self, token: &str) -> Result<Option<String>, AppError>;
+    fn delete_session(&self, token: &str) -> Result<(), AppError>;
+}
+
+/// Build the backend named by the `DATABASE_URL` environment variable: a
+/// `postgres://`/`postgresql://` URL selects Postgres, anything else (or an
+/// unset variable) falls back to the local SQLite file.
+pub fn storage_from_env() -> Result<SharedStorage, AppError> {
+    match std::env::var("DATABASE_URL") {
+        Ok(url) if url.starts_with("postgres://") || url.starts_with("postgresql://") => {
+            info!("Using Postgres storage backend");
+            Ok(Arc::new(PostgresStorage::connect(&url)?))
+        }
+        Ok(path) => {
+            info!("Using SQLite storage backend at {}", path);
+            Ok(Arc::new(SqliteStorage::open(&path)?))
+        }
+        Err(_) => {
+            info!("DATABASE_URL unset, using SQLite storage backend at blog.db");
+            Ok(Arc::new(SqliteStorage::open("blog.db")?))
+        }
+    }
+}
+
+fn pool_error(e: impl std::fmt::Debug) -> AppError {
+    error!("Database pool error: {:?}", e);
+    internal_server_error()
+}
+
+fn query_error(e: impl std::fmt::Debug) -> AppError {
+    error!("Database query error: {:?}", e);
+    internal_server_error()
+}
+
+// Map a failed `INSERT INTO users` to a 409 when it collided with the
+// `username` primary key, and to a generic 500 for any other failure. This is
+// the only uniqueness check `create_user` relies on: when two concurrent
+// registrations for the same username race, only one `INSERT` can win, and
+// the loser gets a proper 409 instead of a generic 500.
+fn create_user_error_sqlite(e: rusqlite::Error) -> AppError {
+    match &e {
+        rusqlite::Error::SqliteFailure(ffi_error, _)
+            if ffi_error.code == rusqlite::ErrorCode::ConstraintViolation =>
+        {
+            warn!("Registration lost a race on an already-taken username");
+            username_taken_error()
+        }
+        _ => query_error(e),
+    }
+}
+
+fn create_user_error_postgres(e: postgres::Error) -> AppError {
+    if e.code() == Some(&postgres::error::SqlState::UNIQUE_VIOLATION) {
+        warn!("Registration lost a race on an already-taken username");
+        username_taken_error()
+    } else {
+        query_error(e)
+    }
+}
+
+/// SQLite backend backed by an r2d2 connection pool so we stop opening a fresh
+/// connection on every request.
+pub struct SqliteStorage {
+    pool: r2d2::Pool<SqliteConnectionManager>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<Self, AppError> {
+        let manager = SqliteConnectionManager::file(path);
+        let pool = r2d2::Pool::new(manager).map_err(pool_error)?;
+        Ok(Self { pool })
+    }
+
+    #[cfg(test)]
+    pub fn in_memory() -> Result<Self, AppError> {
+        // A single shared connection so the in-memory schema is visible to
+        // every checkout during a test.
+        let manager = SqliteConnectionManager::memory();
+        let pool = r2d2::Pool::builder()
+            .max_size(1)
+            .build(manager)
+            .map_err(pool_error)?;
+        let storage = Self { pool };
+        storage.create_schema()?;
+        Ok(storage)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn create_schema(&self) -> Result<(), AppError> {
+        blocking(|| {
+            let conn = self.pool.get().map_err(pool_error)?;
+            conn.execute_batch(SCHEMA_SQLITE).map_err(query_error)?;
+            Ok(())
+        })
+    }
+
+    fn insert_blogpost(&self, blogpost: Blogpost) -> Result<(), AppError> {
+        blocking(|| {
+            let conn = self.pool.get().map_err(pool_error)?;
+            conn.execute(
+                "
+                INSERT INTO blogposts (text, publication_date, image, image_thumbnail, username, avatar)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6);
+                ",
+                rusqlite::params![
+                    blogpost.text,
+                    blogpost.published,
+                    blogpost.image_id,
+                    blogpost.image_thumb_id,
+                    blogpost.author_username,
+                    blogpost.avatar_id,
+                ],
+            )
+            .map_err(query_error)?;
+            Ok(())
+        })
+    }
+
+    fn get_all_blogposts(&self) -> Result<Vec<Blogpost>, AppError> {
+        blocking(|| {
+            let conn = self.pool.get().map_err(pool_error)?;
+            let mut stmt = conn.prepare(&format!("{};", SELECT_ALL)).map_err(query_error)?;
+            let blogposts = stmt
+                .query_map([], |row| Ok(Blogpost::from_sqlite_row(row)))
+                .map_err(query_error)?
+                .collect::<rusqlite::Result<Vec<Blogpost>>>()
+                .map_err(query_error)?
+                .into_iter()
+                .rev()
+                .collect();
+            Ok(blogposts)
+        })
+    }
+
+    fn get_by_id(&self, id: i64) -> Result<Option<Blogpost>, AppError> {
+        blocking(|| {
+            let conn = self.pool.get().map_err(pool_error)?;
+            let mut stmt = conn
+                .prepare(&format!("{} WHERE id = ?1;", SELECT_ALL))
+                .map_err(query_error)?;
+            let post = stmt
+                .query_row([id], |row| Ok(Blogpost::from_sqlite_row(row)))
+                .map(Some)
+                .or_else(|e| match e {
+                    rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                    other => Err(query_error(other)),
+                })?;
+            Ok(post)
+        })
+    }
+
+    fn create_user(&self, username: &str, password_hash: &str) -> Result<(), AppError> {
+        blocking(|| {
+            let conn = self.pool.get().map_err(pool_error)?;
+            conn.execute(
+                "INSERT INTO users (username, password_hash) VALUES (?1, ?2);",
+                rusqlite::params![username, password_hash],
+            )
+            .map_err(create_user_error_sqlite)?;
+            Ok(())
+        })
+    }
+
+    fn get_user(&self, username: &str) -> Result<Option<User>, AppError> {
+        blocking(|| {
+            let conn = self.pool.get().map_err(pool_error)?;
+            let mut stmt = conn
+                .prepare("SELECT username, password_hash FROM users WHERE username = ?1;")
+                .map_err(query_error)?;
+            stmt.query_row([username], |row| {
+                Ok(User {
+                    username: row.get(0)?,
+                    password_hash: row.get(1)?,
+                })
+            })
+            .map(Some)
+            .or_else(|e| match e {
+                rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                other => Err(query_error(other)),
+            })
+        })
+    }
+
+    fn create_session(&self, token: &str, username: &str) -> Result<(), AppError> {
+        blocking(|| {
+            let conn = self.pool.get().map_err(pool_error)?;
+            conn.execute(
+                "INSERT INTO sessions (token, username, created) VALUES (?1, ?2, ?3);",
+                rusqlite::params![token, username, chrono::Local::now().naive_local()],
+            )
+            .map_err(query_error)?;
+            Ok(())
+        })
+    }
+
+    fn session_user(&self, token: &str) -> Result<Option<String>, AppError> {
+        blocking(|| {
+            let conn = self.pool.get().map_err(pool_error)?;
+            let mut stmt = conn
+                .prepare("SELECT username FROM sessions WHERE token = ?1;")
+                .map_err(query_error)?;
+            stmt.query_row([token], |row| row.get::<_, String>(0))
+                .map(Some)
+                .or_else(|e| match e {
+                    rusqlite::Error::QueryReturnedNoRows => Ok(None),
+                    other => Err(query_error(other)),
+                })
+        })
+    }
+
+    fn delete_session(&self, token: &str) -> Result<(), AppError> {
+        blocking(|| {
+            let conn = self.pool.get().map_err(pool_error)?;
+            conn.execute("DELETE FROM sessions WHERE token = ?1;", [token])
+                .map_err(query_error)?;
+            Ok(())
+        })
+    }
+}
+
+/// Postgres backend for deployments that can't rely on a local SQLite file.
+pub struct PostgresStorage {
+    pool: r2d2::Pool<PostgresConnectionManager<postgres::NoTls>>,
+}
+
+impl PostgresStorage {
+    pub fn connect(url: &str) -> Result<Self, AppError> {
+        let config = url.parse().map_err(query_error)?;
+        let manager = PostgresConnectionManager::new(config, postgres::NoTls);
+        let pool = r2d2::Pool::new(manager).map_err(pool_error)?;
+        Ok(Self { pool })
+    }
+}
+
+impl Storage for PostgresStorage {
+    fn create_schema(&self) -> Result<(), AppError> {
+        blocking(|| {
+            let mut conn = self.pool.get().map_err(pool_error)?;
+            conn.batch_execute(SCHEMA_POSTGRES).map_err(query_error)?;
+            Ok(())
+        })
+    }
+
+    fn insert_blogpost(&self, blogpost: Blogpost) -> Result<(), AppError> {
+        blocking(|| {
+            let mut conn = self.pool.get().map_err(pool_error)?;
+            conn.execute(
+                "
+                INSERT INTO blogposts (text, publication_date, image, image_thumbnail, username, avatar)
+                VALUES ($1, $2, $3, $4, $5, $6);
+                ",
+                &[
+                    &blogpost.text,
+                    &blogpost.published,
+                    &blogpost.image_id.as_ref().map(MediaId::as_str),
+                    &blogpost.image_thumb_id.as_ref().map(MediaId::as_str),
+                    &blogpost.author_username,
+                    &blogpost.avatar_id.as_ref().map(MediaId::as_str),
+                ],
+            )
+            .map_err(query_error)?;
+            Ok(())
+        })
+    }
+
+    fn get_all_blogposts(&self) -> Result<Vec<Blogpost>, AppError> {
+        blocking(|| {
+            let mut conn = self.pool.get().map_err(pool_error)?;
+            let rows = conn
+                .query(&format!("{} ORDER BY id DESC;", SELECT_ALL), &[])
+                .map_err(query_error)?;
+            Ok(rows.iter().map(Blogpost::from_postgres_row).collect())
+        })
+    }
+
+    fn get_by_id(&self, id: i64) -> Result<Option<Blogpost>, AppError> {
+        blocking(|| {
+            let mut conn = self.pool.get().map_err(pool_error)?;
+            let row = conn
+                .query_opt(&format!("{} WHERE id = $1;", SELECT_ALL), &[&id])
+                .map_err(query_error)?;
+            Ok(row.as_ref().map(Blogpost::from_postgres_row))
+        })
+    }
+
+    fn create_user(&self, username: &str, password_hash: &str) -> Result<(), AppError> {
+        blocking(|| {
+            let mut conn = self.pool.get().map_err(pool_error)?;
+            conn.execute(
+                "INSERT INTO users (username, password_hash) VALUES ($1, $2);",
+                &[&username, &password_hash],
+            )
+            .map_err(create_user_error_postgres)?;
+            Ok(())
+        })
+    }
+
+    fn get_user(&self, username: &str) -> Result<Option<User>, AppError> {
+        blocking(|| {
+            let mut conn = self.pool.get().map_err(pool_error)?;
+            let row = conn
+                .query_opt(
+                    "SELECT username, password_hash FROM users WHERE username = $1;",
+                    &[&username],
+                )
+                .map_err(query_error)?;
+            Ok(row.map(|row| User {
+                username: row.get(0),
+                password_hash: row.get(1),
+            }))
+        })
+    }
+
+    fn create_session(&self, token: &str, username: &str) -> Result<(), AppError> {
+        blocking(|| {
+            let mut conn = self.pool.get().map_err(pool_error)?;
+            conn.execute(
+                "INSERT INTO sessions (token, username, created) VALUES ($1, $2, $3);",
+                &[&token, &username, &chrono::Local::now().naive_local()],
+            )
+            .map_err(query_error)?;
+            Ok(())
+        })
+    }
+
+    fn session_user(&self, token: &str) -> Result<Option<String>, AppError> {
+        blocking(|| {
+            let mut conn = self.pool.get().map_err(pool_error)?;
+            let row = conn
+                .query_opt("SELECT username FROM sessions WHERE token = $1;", &[&token])
+                .map_err(query_error)?;
+            Ok(row.map(|row| row.get(0)))
+        })
+    }
+
+    fn delete_session(&self, token: &str) -> Result<(), AppError> {
+        blocking(|| {
+            let mut conn = self.pool.get().map_err(pool_error)?;
+            conn.execute("DELETE FROM sessions WHERE token = $1;", &[&token])
+                .map_err(query_error)?;
+            Ok(())
+        })
+    }
+}
+
+// Both backends do blocking work under the hood: Postgres waits on a network
+// round-trip, and SQLite's `r2d2::Pool::get` can block on a checked-out
+// connection while `rusqlite` fsyncs on commit. Running either synchronously
+// on a Tokio worker thread would starve the runtime under load, so every
+// `Storage` method runs through `block_in_place`, which tells the runtime
+// this thread is about to block so it can hand off its other work first.
+fn blocking<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    tokio::task::block_in_place(f)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn create_user_rejects_duplicate_username() {
+        let storage = SqliteStorage::in_memory().unwrap();
+        storage.create_user("alice", "hash").unwrap();
+
+        let err = storage.create_user("alice", "another-hash").unwrap_err();
+        assert_eq!(err, username_taken_error());
+    }
 }
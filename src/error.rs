@@ -2,6 +2,7 @@ use axum::{
     http::StatusCode,
     response::{Html, IntoResponse},
 };
+use image::ImageFormat;
 
 #[derive(Debug, PartialEq)]
 pub struct AppError {
@@ -40,9 +41,16 @@ pub fn form_error() -> AppError {
     AppError::new("Invalid form data.", StatusCode::BAD_REQUEST)
 }
 
-pub fn invalid_image_format_error() -> AppError {
+/// Lists the caller's configured allow-list rather than a fixed string, so
+/// the message stays accurate once `ACCEPTED_IMAGE_FORMATS` narrows it.
+pub fn invalid_image_format_error(accepted_formats: &[ImageFormat]) -> AppError {
+    let formats = accepted_formats
+        .iter()
+        .map(|format| format!("{:?}", format).to_uppercase())
+        .collect::<Vec<_>>()
+        .join(", ");
     AppError::new(
-        "Invalid image format. Accepting only PNG.",
+        &format!("Invalid image format. Accepted formats: {}.", formats),
         StatusCode::BAD_REQUEST,
     )
 }
@@ -50,3 +58,30 @@ pub fn invalid_image_format_error() -> AppError {
 pub fn invalid_avatar_url_error() -> AppError {
     AppError::new("Invalid avatar URL.", StatusCode::BAD_REQUEST)
 }
+
+pub fn unauthorized_error() -> AppError {
+    AppError::new("Authentication required.", StatusCode::UNAUTHORIZED)
+}
+
+pub fn username_taken_error() -> AppError {
+    AppError::new("Username is already taken.", StatusCode::CONFLICT)
+}
+
+pub fn payload_too_large_error() -> AppError {
+    AppError::new("Upload exceeds the allowed size.", StatusCode::PAYLOAD_TOO_LARGE)
+}
+
+pub fn invalid_media_id_error() -> AppError {
+    AppError::new("Invalid media id.", StatusCode::BAD_REQUEST)
+}
+
+pub fn media_not_found_error() -> AppError {
+    AppError::new("Media not found.", StatusCode::NOT_FOUND)
+}
+
+pub fn image_dimensions_too_large_error() -> AppError {
+    AppError::new(
+        "Image dimensions exceed the allowed limit.",
+        StatusCode::PAYLOAD_TOO_LARGE,
+    )
+}
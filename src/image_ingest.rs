@@ -0,0 +1,309 @@
+use crate::error::{
+    image_dimensions_too_large_error, internal_server_error, invalid_image_format_error, AppError,
+};
+use axum::body::Bytes;
+use image::{ImageFormat, ImageReader};
+use log::{error, warn};
+use std::io::Cursor;
+
+/// Canonical content type every ingested image is re-encoded to.
+pub const CANONICAL_CONTENT_TYPE: &str = "image/png";
+
+/// Knobs controlling which uploads are accepted and how previews are sized.
+#[derive(Clone, Debug)]
+pub struct ImageIngestConfig {
+    /// Input formats we are willing to decode before normalizing to PNG.
+    pub accepted_formats: Vec<ImageFormat>,
+    /// Longest-edge bound, in pixels, for the generated thumbnail.
+    pub thumbnail_max_edge: u32,
+    /// Upper bound on `width * height` we are willing to decode. Checked
+    /// against the header-declared dimensions before `decode` runs, so a
+    /// small file with a huge declared size (a decompression bomb) is
+    /// rejected before it can balloon memory.
+    pub max_decoded_pixels: u64,
+}
+
+impl Default for ImageIngestConfig {
+    fn default() -> Self {
+        Self {
+            accepted_formats: vec![
+                ImageFormat::Png,
+                ImageFormat::Jpeg,
+                ImageFormat::WebP,
+                ImageFormat::Gif,
+            ],
+            thumbnail_max_edge: 320,
+            max_decoded_pixels: 40_000_000,
+        }
+    }
+}
+
+impl ImageIngestConfig {
+    /// Whether a response `Content-Type` advertises one of the accepted input
+    /// formats. Used to reject avatars before we bother downloading the body.
+    pub fn accepts_content_type(&self, content_type: &str) -> bool {
+        // Strip any `; charset=...` parameters before matching.
+        let mime = content_type.split(';').next().unwrap_or("").trim();
+        ImageFormat::from_mime_type(mime)
+            .map(|format| self.accepted_formats.contains(&format))
+            .unwrap_or(false)
+    }
+
+    /// Build ingest settings from the environment, falling back to
+    /// `Default::default()` for anything unset or malformed, the same way
+    /// `db::storage_from_env` falls back to a local SQLite file.
+    /// `ACCEPTED_IMAGE_FORMATS` is a comma-separated list of format names
+    /// (e.g. `png,jpeg,webp,gif`); `THUMBNAIL_MAX_EDGE` is the longest-edge
+    /// pixel bound for generated thumbnails.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+
+        let accepted_formats = match std::env::var("ACCEPTED_IMAGE_FORMATS") {
+            Ok(raw) => parse_accepted_formats(&raw).unwrap_or_else(|| {
+                warn!(
+                    "ACCEPTED_IMAGE_FORMATS={:?} contains an unrecognized format, using the default allow-list",
+                    raw
+                );
+                default.accepted_formats.clone()
+            }),
+            Err(_) => default.accepted_formats.clone(),
+        };
+
+        let thumbnail_max_edge = match std::env::var("THUMBNAIL_MAX_EDGE") {
+            Ok(raw) => raw.parse().unwrap_or_else(|_| {
+                warn!(
+                    "THUMBNAIL_MAX_EDGE={:?} is not a valid pixel count, using the default of {}",
+                    raw, default.thumbnail_max_edge
+                );
+                default.thumbnail_max_edge
+            }),
+            Err(_) => default.thumbnail_max_edge,
+        };
+
+        Self {
+            accepted_formats,
+            thumbnail_max_edge,
+            max_decoded_pixels: default.max_decoded_pixels,
+        }
+    }
+}
+
+// Parse a comma-separated list of format names into `ImageFormat`s, rejecting
+// the whole list if any entry is unrecognized so a typo can't silently narrow
+// the allow-list instead of being caught.
+fn parse_accepted_formats(raw: &str) -> Option<Vec<ImageFormat>> {
+    raw.split(',')
+        .map(|name| ImageFormat::from_extension(name.trim()))
+        .collect()
+}
+
+/// A decoded upload re-encoded to a canonical PNG together with a bounded
+/// thumbnail, both ready to hand to the media store.
+pub struct NormalizedImage {
+    pub full_png: Bytes,
+    pub thumbnail_png: Bytes,
+}
+
+/// Decode `bytes` (guessing the format and checking it against the allow-list),
+/// re-encode the full image to a metadata-free PNG, and produce a thumbnail
+/// whose longest edge is at most `config.thumbnail_max_edge`.
+pub fn normalize(bytes: &[u8], config: &ImageIngestConfig) -> Result<NormalizedImage, AppError> {
+    let reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| {
+            error!("Failed to guess image format: {:?}", e);
+            internal_server_error()
+        })?;
+
+    match reader.format() {
+        Some(format) if config.accepted_formats.contains(&format) => {}
+        Some(format) => {
+            warn!("Rejected unsupported image format: {:?}", format);
+            return Err(invalid_image_format_error(&config.accepted_formats));
+        }
+        None => {
+            warn!("No image format detected");
+            return Err(invalid_image_format_error(&config.accepted_formats));
+        }
+    }
+
+    // Read the declared dimensions straight out of the header, without
+    // decoding the pixel data, so we can reject an oversized image before
+    // `decode` allocates the full bitmap.
+    let (width, height) = reader.into_dimensions().map_err(|e| {
+        warn!("Failed to read image dimensions: {:?}", e);
+        invalid_image_format_error(&config.accepted_formats)
+    })?;
+    reject_if_too_large(width, height, config.max_decoded_pixels)?;
+
+    // `into_dimensions` consumed the reader above; re-open the same bytes to
+    // actually decode the pixel data.
+    let reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| {
+            error!("Failed to guess image format: {:?}", e);
+            internal_server_error()
+        })?;
+    let image = reader.decode().map_err(|e| {
+        warn!("Failed to decode image: {:?}", e);
+        invalid_image_format_error(&config.accepted_formats)
+    })?;
+
+    let thumbnail = make_thumbnail(&image, config.thumbnail_max_edge);
+
+    Ok(NormalizedImage {
+        full_png: encode_png(&image)?,
+        thumbnail_png: encode_png(&thumbnail)?,
+    })
+}
+
+// Reject a declared width/height whose pixel count would exceed the
+// configured ceiling, before `decode` gets a chance to allocate it.
+fn reject_if_too_large(width: u32, height: u32, max_pixels: u64) -> Result<(), AppError> {
+    let pixels = width as u64 * height as u64;
+    if pixels > max_pixels {
+        warn!(
+            "Rejected {}x{} image ({} pixels), exceeds the {} pixel ceiling",
+            width, height, pixels, max_pixels
+        );
+        return Err(image_dimensions_too_large_error());
+    }
+    Ok(())
+}
+
+// Downscale the image so its longest edge fits within `max_edge`, preserving
+// the aspect ratio with a Lanczos3 filter. Images already within bounds are
+// left untouched so we never upscale.
+fn make_thumbnail(image: &image::DynamicImage, max_edge: u32) -> image::DynamicImage {
+    let longest = image.width().max(image.height());
+    if longest <= max_edge {
+        return image.clone();
+    }
+    image.resize(max_edge, max_edge, image::imageops::FilterType::Lanczos3)
+}
+
+// Encode to PNG. Going through the decoder and back drops any EXIF/ICC/other
+// metadata the original carried.
+fn encode_png(image: &image::DynamicImage) -> Result<Bytes, AppError> {
+    let mut buffer = Cursor::new(Vec::new());
+    image.write_to(&mut buffer, ImageFormat::Png).map_err(|e| {
+        error!("Failed to encode image as PNG: {:?}", e);
+        internal_server_error()
+    })?;
+    Ok(Bytes::from(buffer.into_inner()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, RgbImage};
+
+    fn encode_test_png(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(width, height));
+        let mut buffer = Cursor::new(Vec::new());
+        image.write_to(&mut buffer, ImageFormat::Png).unwrap();
+        buffer.into_inner()
+    }
+
+    // Standard CRC-32 (IEEE 802.3), as required by the PNG chunk format.
+    fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc: u32 = 0xFFFF_FFFF;
+        for &byte in bytes {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (crc & 1).wrapping_neg();
+                crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
+        !crc
+    }
+
+    fn png_chunk(chunk_type: &[u8], data: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let mut type_and_data = Vec::with_capacity(chunk_type.len() + data.len());
+        type_and_data.extend_from_slice(chunk_type);
+        type_and_data.extend_from_slice(data);
+        out.extend_from_slice(&type_and_data);
+        out.extend_from_slice(&crc32(&type_and_data).to_be_bytes());
+    }
+
+    // A syntactically valid PNG carrying only an IHDR declaring `width` x
+    // `height` and an empty IEND — no pixel data at all. Used to prove the
+    // dimension guard fires from the header alone, before any decode is
+    // attempted.
+    fn png_with_fake_dimensions(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = vec![0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&width.to_be_bytes());
+        ihdr.extend_from_slice(&height.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8-bit truecolor, no interlace
+        png_chunk(b"IHDR", &ihdr, &mut bytes);
+        png_chunk(b"IEND", &[], &mut bytes);
+        bytes
+    }
+
+    #[test]
+    fn parse_accepted_formats_accepts_known_names() {
+        let formats = parse_accepted_formats("png, jpeg").unwrap();
+        assert_eq!(formats, vec![ImageFormat::Png, ImageFormat::Jpeg]);
+    }
+
+    #[test]
+    fn parse_accepted_formats_rejects_unknown_names() {
+        assert!(parse_accepted_formats("png,not-a-format").is_none());
+    }
+
+    #[test]
+    fn reject_if_too_large_allows_images_within_the_ceiling() {
+        assert!(reject_if_too_large(100, 100, 40_000_000).is_ok());
+    }
+
+    #[test]
+    fn reject_if_too_large_rejects_images_over_the_ceiling() {
+        let err = reject_if_too_large(20_000, 20_000, 40_000_000).unwrap_err();
+        assert_eq!(err, image_dimensions_too_large_error());
+    }
+
+    #[test]
+    fn normalize_accepts_a_small_png() {
+        let bytes = encode_test_png(4, 4);
+        let result = normalize(&bytes, &ImageIngestConfig::default()).unwrap();
+        assert!(!result.full_png.is_empty());
+        assert!(!result.thumbnail_png.is_empty());
+    }
+
+    #[test]
+    fn normalize_rejects_a_disallowed_format() {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(4, 4));
+        let mut buffer = Cursor::new(Vec::new());
+        image.write_to(&mut buffer, ImageFormat::Bmp).unwrap();
+
+        let err = normalize(&buffer.into_inner(), &ImageIngestConfig::default()).unwrap_err();
+        assert_eq!(
+            err,
+            invalid_image_format_error(&ImageIngestConfig::default().accepted_formats)
+        );
+    }
+
+    #[test]
+    fn normalize_rejects_huge_declared_dimensions_before_decode() {
+        let bytes = png_with_fake_dimensions(20_000, 20_000);
+        let err = normalize(&bytes, &ImageIngestConfig::default()).unwrap_err();
+        assert_eq!(err, image_dimensions_too_large_error());
+    }
+
+    #[test]
+    fn make_thumbnail_downscales_past_the_max_edge() {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(800, 200));
+        let thumbnail = make_thumbnail(&image, 320);
+        assert!(thumbnail.width() <= 320 && thumbnail.height() <= 320);
+        assert!(thumbnail.width() < image.width());
+    }
+
+    #[test]
+    fn make_thumbnail_leaves_small_images_untouched() {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(100, 50));
+        let thumbnail = make_thumbnail(&image, 320);
+        assert_eq!((thumbnail.width(), thumbnail.height()), (100, 50));
+    }
+}
@@ -1,25 +1,45 @@
+mod auth;
 mod backend;
 mod blogpost;
 mod db;
 mod error;
+mod image_ingest;
+mod media;
 
-use crate::backend::{fallback, get_home, handle_form_submit};
-use axum::{response::Redirect, routing::get, Router};
+use crate::auth::{post_login, post_logout, post_register};
+use crate::backend::{fallback, get_home, get_media, handle_form_submit, max_request_body_bytes};
+use crate::db::SharedStorage;
+use axum::{
+    extract::DefaultBodyLimit,
+    response::Redirect,
+    routing::{get, post},
+    Router,
+};
 
-fn app() -> Router {
+fn app(storage: SharedStorage) -> Router {
     axum::Router::new()
         .fallback(fallback)
         .route("/", get(|| async { Redirect::permanent("/home") }))
         .route("/home", get(get_home).post(handle_form_submit))
+        .route("/media/:id", get(get_media))
+        .route("/login", post(post_login))
+        .route("/logout", post(post_logout))
+        .route("/register", post(post_register))
+        // Axum's implicit default body limit (2MB) runs ahead of our own
+        // streaming/size-budget checks in `parse_multipart`, so raise it to
+        // match `UploadLimits` or those checks never see anything bigger.
+        .layer(DefaultBodyLimit::max(max_request_body_bytes()))
+        .with_state(storage)
 }
 
 #[tokio::main]
 async fn main() {
     env_logger::init();
-    db::create_db_schema().unwrap();
+    let storage = db::storage_from_env().expect("failed to initialize storage backend");
+    storage.create_schema().expect("failed to create schema");
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app()).await.unwrap();
+    axum::serve(listener, app(storage)).await.unwrap();
 }
 
 #[cfg(test)]
@@ -29,13 +49,19 @@ mod tests {
         body::{to_bytes, Body},
         extract::Request,
     };
+    use std::sync::Arc;
     use tower::ServiceExt;
 
     use super::*;
 
-    #[tokio::test]
+    fn test_app() -> Router {
+        let storage = db::SqliteStorage::in_memory().unwrap();
+        app(Arc::new(storage))
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
     async fn nonexisting_url_returns_404() {
-        let response = app()
+        let response = test_app()
             .oneshot(
                 Request::builder()
                     .uri("/nonexisting")
@@ -47,18 +73,18 @@ mod tests {
         assert_eq!(response.status(), 404);
     }
 
-    #[tokio::test]
+    #[tokio::test(flavor = "multi_thread")]
     async fn root_uri_returns_redirect() {
-        let response = app()
+        let response = test_app()
             .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
             .await
             .unwrap();
         assert_eq!(response.status(), 308);
     }
 
-    #[tokio::test]
+    #[tokio::test(flavor = "multi_thread")]
     async fn home_uri_returns_home_page() {
-        let response = app()
+        let response = test_app()
             .oneshot(Request::builder().uri("/home").body(Body::empty()).unwrap())
             .await
             .unwrap();
@@ -0,0 +1,294 @@
+use crate::error::{internal_server_error, invalid_media_id_error, media_not_found_error, AppError};
+use async_trait::async_trait;
+use axum::body::Bytes;
+use log::{error, info};
+use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Default directory under which the filesystem-backed store keeps its blobs.
+pub const DEFAULT_MEDIA_ROOT: &str = "media";
+
+/// Content-addressed identifier for a stored blob: the lowercase hex
+/// SHA-256 of its bytes. Identical uploads collapse onto the same id, so the
+/// store dedupes for free and the id is safe to cache forever.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MediaId(String);
+
+impl MediaId {
+    /// Derive an id from the bytes it identifies.
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let digest = Sha256::digest(bytes);
+        Self(hex::encode(digest))
+    }
+
+    /// Parse an id coming from an untrusted source (e.g. a route param),
+    /// rejecting anything that isn't a well-formed SHA-256 hex digest so it
+    /// can never escape the store's directory.
+    pub fn parse(raw: &str) -> Result<Self, AppError> {
+        let is_valid = raw.len() == 64 && raw.bytes().all(|b| b.is_ascii_hexdigit());
+        if is_valid {
+            Ok(Self(raw.to_ascii_lowercase()))
+        } else {
+            warn_invalid(raw);
+            Err(invalid_media_id_error())
+        }
+    }
+
+    /// Wrap a value that is already known to be a valid id — e.g. one read back
+    /// from our own database — without re-validating it.
+    pub(crate) fn from_trusted(raw: String) -> Self {
+        Self(raw)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+fn warn_invalid(raw: &str) {
+    log::warn!("Rejected malformed media id: {}", raw);
+}
+
+impl std::fmt::Display for MediaId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::fmt::Debug for MediaId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MediaId({})", &self.0)
+    }
+}
+
+impl ToSql for MediaId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0.as_str()))
+    }
+}
+
+impl FromSql for MediaId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str().map(|s| MediaId(s.to_string()))
+    }
+}
+
+/// An open handle to a stored blob together with its size and content type —
+/// enough for a caller to build a streamed response without ever reading the
+/// whole file into memory.
+pub struct OpenMedia {
+    pub file: tokio::fs::File,
+    pub size: u64,
+    pub content_type: String,
+}
+
+/// A place to persist and retrieve opaque media blobs together with the
+/// content type they should be served with.
+#[async_trait]
+pub trait MediaStore: Send + Sync {
+    async fn store(&self, bytes: Bytes, content_type: &str) -> Result<MediaId, AppError>;
+    /// Open a stored blob for reading. Returns a live file handle rather than
+    /// buffered bytes so callers can stream it back instead of holding the
+    /// whole blob in memory.
+    async fn open(&self, id: &MediaId) -> Result<OpenMedia, AppError>;
+    /// Last modification time of the stored blob, used to emit a
+    /// `Last-Modified` header and honor `If-Modified-Since`.
+    async fn modified(&self, id: &MediaId) -> Result<Option<SystemTime>, AppError>;
+}
+
+/// Filesystem-backed [`MediaStore`] that writes each blob to a
+/// content-addressed path sharded by the first two bytes of its hash
+/// (`<root>/ab/cd/<hash>`) to keep directories small. The content type is kept
+/// in a sibling `<hash>.type` sidecar.
+pub struct FilesystemMediaStore {
+    root: PathBuf,
+}
+
+impl FilesystemMediaStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn blob_path(&self, id: &MediaId) -> PathBuf {
+        let hash = id.as_str();
+        self.root.join(&hash[0..2]).join(&hash[2..4]).join(hash)
+    }
+
+    fn type_path(&self, id: &MediaId) -> PathBuf {
+        let mut path = self.blob_path(id);
+        path.set_extension("type");
+        path
+    }
+}
+
+#[async_trait]
+impl MediaStore for FilesystemMediaStore {
+    async fn store(&self, bytes: Bytes, content_type: &str) -> Result<MediaId, AppError> {
+        let id = MediaId::from_bytes(&bytes);
+        let blob_path = self.blob_path(&id);
+
+        if tokio::fs::try_exists(&blob_path).await.unwrap_or(false) {
+            info!("Media {} already present, skipping write", id);
+            return Ok(id);
+        }
+
+        if let Some(parent) = blob_path.parent() {
+            create_dir(parent).await?;
+        }
+        write_file(&blob_path, &bytes).await?;
+        write_file(&self.type_path(&id), content_type.as_bytes()).await?;
+
+        info!("Stored {} bytes of media as {}", bytes.len(), id);
+        Ok(id)
+    }
+
+    async fn open(&self, id: &MediaId) -> Result<OpenMedia, AppError> {
+        let file = tokio::fs::File::open(self.blob_path(id))
+            .await
+            .map_err(|e| read_error(id, "open", e))?;
+        let size = file
+            .metadata()
+            .await
+            .map_err(|e| read_error(id, "stat", e))?
+            .len();
+        let content_type = tokio::fs::read_to_string(self.type_path(id))
+            .await
+            .unwrap_or_else(|_| "application/octet-stream".to_string());
+        Ok(OpenMedia {
+            file,
+            size,
+            content_type,
+        })
+    }
+
+    async fn modified(&self, id: &MediaId) -> Result<Option<SystemTime>, AppError> {
+        let metadata = tokio::fs::metadata(self.blob_path(id))
+            .await
+            .map_err(|e| read_error(id, "stat", e))?;
+        Ok(metadata.modified().ok())
+    }
+}
+
+// Map a failed read/stat of a blob to a 404 when the blob simply isn't there,
+// and to a 500 for any other I/O failure.
+fn read_error(id: &MediaId, action: &str, e: std::io::Error) -> AppError {
+    if e.kind() == std::io::ErrorKind::NotFound {
+        info!("Media {} not found while trying to {} it", id, action);
+        media_not_found_error()
+    } else {
+        error!("Failed to {} media {}: {:?}", action, id, e);
+        internal_server_error()
+    }
+}
+
+async fn create_dir(path: &Path) -> Result<(), AppError> {
+    tokio::fs::create_dir_all(path).await.map_err(|e| {
+        error!("Failed to create media directory {:?}: {:?}", path, e);
+        internal_server_error()
+    })
+}
+
+async fn write_file(path: &Path, bytes: &[u8]) -> Result<(), AppError> {
+    tokio::fs::write(path, bytes).await.map_err(|e| {
+        error!("Failed to write media file {:?}: {:?}", path, e);
+        internal_server_error()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    // Each test gets its own scratch directory under the OS temp dir, keyed
+    // by test name plus the running thread, so parallel tests never collide.
+    fn test_store(name: &str) -> (FilesystemMediaStore, PathBuf) {
+        let root = std::env::temp_dir().join(format!(
+            "blogpost-media-test-{}-{:?}",
+            name,
+            std::thread::current().id()
+        ));
+        let _ = std::fs::remove_dir_all(&root);
+        (FilesystemMediaStore::new(root.clone()), root)
+    }
+
+    #[test]
+    fn media_id_parse_accepts_valid_hex() {
+        let hash = "a".repeat(64);
+        assert!(MediaId::parse(&hash).is_ok());
+    }
+
+    #[test]
+    fn media_id_parse_lowercases_input() {
+        let hash = "A".repeat(64);
+        let id = MediaId::parse(&hash).unwrap();
+        assert_eq!(id.as_str(), "a".repeat(64));
+    }
+
+    #[test]
+    fn media_id_parse_rejects_wrong_length() {
+        let hash = "a".repeat(63);
+        assert_eq!(MediaId::parse(&hash), Err(invalid_media_id_error()));
+    }
+
+    #[test]
+    fn media_id_parse_rejects_non_hex() {
+        let hash = "g".repeat(64);
+        assert_eq!(MediaId::parse(&hash), Err(invalid_media_id_error()));
+    }
+
+    #[tokio::test]
+    async fn store_is_content_addressed_and_dedupes() {
+        let (store, root) = test_store("dedupe");
+
+        let first = store
+            .store(Bytes::from_static(b"hello"), "text/plain")
+            .await
+            .unwrap();
+        let second = store
+            .store(Bytes::from_static(b"hello"), "text/plain")
+            .await
+            .unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(first, MediaId::from_bytes(b"hello"));
+
+        let mut media = store.open(&first).await.unwrap();
+        let mut bytes = Vec::new();
+        media.file.read_to_end(&mut bytes).await.unwrap();
+        assert_eq!(bytes, b"hello");
+        assert_eq!(media.size, 5);
+        assert_eq!(media.content_type, "text/plain");
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn store_shards_blobs_by_the_first_two_hash_bytes() {
+        let (store, root) = test_store("sharding");
+
+        let id = store
+            .store(Bytes::from_static(b"shard me"), "text/plain")
+            .await
+            .unwrap();
+        let hash = id.as_str();
+
+        let expected_path = root.join(&hash[0..2]).join(&hash[2..4]).join(hash);
+        assert!(expected_path.is_file());
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn open_returns_not_found_for_a_missing_id() {
+        let (store, root) = test_store("missing");
+        let id = MediaId::from_bytes(b"never stored");
+
+        let err = store.open(&id).await.unwrap_err();
+        assert_eq!(err, media_not_found_error());
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}